@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use deadpool_postgres::Pool;
+use futures_util::StreamExt;
+use moka::future::Cache;
+use time::OffsetDateTime;
+use tokio::sync::OnceCell;
+use tower_sessions_core::session::{Id, Record};
+
+use crate::Error;
+
+/// A write-through cache of sessions, keyed by session id.
+///
+/// Entries are evicted lazily once their `expiry_date` passes, and
+/// proactively by [`spawn_invalidation_listener`] whenever another store
+/// instance sharing the same database writes or deletes a session.
+#[derive(Debug)]
+pub(crate) struct SessionCache {
+    entries: Cache<String, (Record, OffsetDateTime)>,
+    listener_started: OnceCell<()>,
+}
+
+impl SessionCache {
+    pub(crate) fn new(capacity: u64) -> Self {
+        Self {
+            entries: Cache::new(capacity),
+            listener_started: OnceCell::new(),
+        }
+    }
+
+    /// Runs `start` exactly once for this cache, no matter how many times
+    /// (or how concurrently) this is called.
+    ///
+    /// This defers spawning the invalidation listener until the store is
+    /// first actually used, rather than when [`with_cache`](crate::PostgresStore::with_cache)
+    /// is called, so it picks up whatever `schema_name`/`table_name` the
+    /// builder chain ends up with instead of baking in whatever they were
+    /// set to at that point in the chain.
+    pub(crate) async fn ensure_listener_started<F>(&self, start: F)
+    where
+        F: FnOnce() -> tokio::task::JoinHandle<()>,
+    {
+        self.listener_started
+            .get_or_init(|| async {
+                start();
+            })
+            .await;
+    }
+
+    pub(crate) async fn get(&self, id: &Id) -> Option<Record> {
+        let (record, expiry_date) = self.entries.get(&id.to_string()).await?;
+        if expiry_date > OffsetDateTime::now_utc() {
+            Some(record)
+        } else {
+            self.invalidate(id).await;
+            None
+        }
+    }
+
+    pub(crate) async fn insert(&self, record: Record) {
+        let key = record.id.to_string();
+        let expiry_date = record.expiry_date;
+        self.entries.insert(key, (record, expiry_date)).await;
+    }
+
+    pub(crate) async fn invalidate(&self, id: &Id) {
+        self.entries.invalidate(&id.to_string()).await;
+    }
+}
+
+/// Spawns a background task that `LISTEN`s on `channel` and evicts the
+/// corresponding entry from `cache` for every session id it's notified
+/// about. The task holds a dedicated connection for the lifetime of the
+/// store and reconnects automatically if that connection is lost.
+pub(crate) fn spawn_invalidation_listener(
+    pool: Pool,
+    channel: String,
+    cache: Arc<SessionCache>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = listen_once(&pool, &channel, &cache).await {
+                tracing::warn!(
+                    error = %err,
+                    "session cache invalidation listener lost its connection, reconnecting"
+                );
+            }
+            tokio::time::sleep(StdDuration::from_secs(1)).await;
+        }
+    })
+}
+
+async fn listen_once(pool: &Pool, channel: &str, cache: &SessionCache) -> Result<(), Error> {
+    let client = pool.get().await.map_err(Error::Pool)?;
+    client
+        .batch_execute(&format!(r#"listen "{channel}""#))
+        .await
+        .map_err(Error::Pg)?;
+
+    let mut notifications = client.notifications();
+    while let Some(notification) = notifications.next().await {
+        let notification = notification.map_err(Error::Pg)?;
+        if let Ok(id) = notification.payload().parse() {
+            cache.invalidate(&id).await;
+        }
+    }
+
+    Ok(())
+}
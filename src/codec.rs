@@ -0,0 +1,52 @@
+use std::fmt;
+
+use tower_sessions_core::session::Record;
+
+use crate::Error;
+
+/// A pluggable (de)serialization format for session [`Record`]s.
+///
+/// Implement this to store sessions in a format other than the default
+/// MessagePack, while keeping the `bytea` column layout. See
+/// [`PostgresStore::with_json_data`](crate::PostgresStore::with_json_data)
+/// if you instead want sessions stored in a queryable `jsonb` column.
+pub trait SessionCodec: fmt::Debug + Send + Sync {
+    /// Serializes a record into its on-disk byte representation.
+    fn encode(&self, record: &Record) -> Result<Vec<u8>, Error>;
+
+    /// Deserializes a record from its on-disk byte representation.
+    fn decode(&self, data: &[u8]) -> Result<Record, Error>;
+}
+
+/// The default codec, serializing records as MessagePack.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePackCodec;
+
+impl SessionCodec for MessagePackCodec {
+    fn encode(&self, record: &Record) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec(record).map_err(Error::Encode)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Record, Error> {
+        rmp_serde::from_slice(data).map_err(Error::Decode)
+    }
+}
+
+/// A codec that serializes records as JSON text, stored in the `bytea`
+/// column like any other codec.
+///
+/// To store sessions in a native `jsonb` column that's queryable with plain
+/// SQL, use [`PostgresStore::with_json_data`](crate::PostgresStore::with_json_data)
+/// instead.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+impl SessionCodec for JsonCodec {
+    fn encode(&self, record: &Record) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(record).map_err(Error::Json)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Record, Error> {
+        serde_json::from_slice(data).map_err(Error::Json)
+    }
+}
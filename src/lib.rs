@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use deadpool_postgres::{GenericClient, Pool};
 use time::OffsetDateTime;
@@ -6,6 +8,13 @@ use tower_sessions_core::{
     session_store, ExpiredDeletion, SessionStore,
 };
 
+mod cache;
+mod codec;
+mod migrations;
+
+use cache::SessionCache;
+pub use codec::{JsonCodec, MessagePackCodec, SessionCodec};
+
 #[derive(Debug, thiserror::Error)]
 #[error("Pg session store error: {0}")]
 pub enum Error {
@@ -29,20 +38,61 @@ pub enum Error {
         #[source]
         rmp_serde::decode::Error,
     ),
+    Json(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    #[error("failed to allocate a unique session id after {0} attempts")]
+    IdCollision(u8),
+    #[error(
+        "the existing \"{table}\".data column is {actual}, but this store is configured for \
+         {expected}; migrating between with_json_data() and the default (or back) requires a \
+         manual column migration"
+    )]
+    DataColumnMismatch {
+        table: String,
+        expected: &'static str,
+        actual: String,
+    },
 }
 
+/// How many times [`SessionStore::create`](tower_sessions_core::SessionStore::create)
+/// regenerates the session id after an `id` collision before giving up.
+const MAX_CREATE_ATTEMPTS: u8 = 8;
+
 impl From<Error> for session_store::Error {
     fn from(e: Error) -> Self {
         Self::Backend(e.to_string())
     }
 }
 
+/// How a [`PostgresStore`] serializes and stores session data in the `data`
+/// column.
+#[derive(Clone, Debug)]
+enum DataFormat {
+    /// A `bytea` column holding bytes produced by a [`SessionCodec`].
+    Binary(Arc<dyn SessionCodec>),
+    /// A `jsonb` column holding the record's `data` map serialized as JSON
+    /// (`id` and `expiry_date` live in their own columns), queryable
+    /// with plain SQL (e.g. `data->>'user_id'`).
+    Json,
+}
+
+impl Default for DataFormat {
+    fn default() -> Self {
+        Self::Binary(Arc::new(MessagePackCodec))
+    }
+}
+
 /// A PostgreSQL session store.
 #[derive(Clone, Debug)]
 pub struct PostgresStore {
     pool: Pool,
     schema_name: String,
     table_name: String,
+    cache: Option<Arc<SessionCache>>,
+    data_format: DataFormat,
 }
 
 impl PostgresStore {
@@ -52,9 +102,70 @@ impl PostgresStore {
             pool,
             schema_name: "tower_sessions".to_string(),
             table_name: "session".to_string(),
+            cache: None,
+            data_format: DataFormat::default(),
         }
     }
 
+    /// Use `codec` to serialize and deserialize session data in the `bytea`
+    /// `data` column, instead of the default [`MessagePackCodec`].
+    pub fn with_codec(mut self, codec: impl SessionCodec + 'static) -> Self {
+        self.data_format = DataFormat::Binary(Arc::new(codec));
+        self
+    }
+
+    /// Store session data as JSON in a native `jsonb` column instead of a
+    /// `bytea` blob, so operators can inspect and query session contents
+    /// with ordinary SQL (e.g. `data->>'user_id'`).
+    pub fn with_json_data(mut self) -> Self {
+        self.data_format = DataFormat::Json;
+        self
+    }
+
+    /// Enable a write-through in-memory cache holding up to `capacity`
+    /// sessions in front of this store.
+    ///
+    /// `load` is served from the cache whenever possible, only falling back
+    /// to Postgres on a miss or once the cached entry's `expiry_date` has
+    /// passed; `save` and `delete` update the cache immediately. Enabling
+    /// the cache also lets [`save`](SessionStore::save) recognize
+    /// sliding-expiry renewals (where only `expiry_date` changed) and take
+    /// the cheap [`touch`](Self::touch) path instead of a full encode +
+    /// upsert; without a cache, `save` always takes the full path. Because
+    /// multiple app instances may share one database, this also spawns a
+    /// background task that `LISTEN`s for invalidations published by every
+    /// instance's `save`/`delete` calls, so a write on one instance evicts
+    /// the stale entry from every other instance's cache. That task starts
+    /// lazily on first use of the store (not when `with_cache` is called),
+    /// so it always subscribes on the channel for the final
+    /// `schema_name`/`table_name`, however they were set.
+    pub fn with_cache(mut self, capacity: u64) -> Self {
+        self.cache = Some(Arc::new(SessionCache::new(capacity)));
+        self
+    }
+
+    fn invalidation_channel(&self) -> String {
+        format!(
+            "tower_sessions_invalidate_{}_{}",
+            self.schema_name, self.table_name
+        )
+    }
+
+    async fn ensure_cache_listener(&self) {
+        let Some(cache) = self.cache.clone() else {
+            return;
+        };
+        let pool = self.pool.clone();
+        let channel = self.invalidation_channel();
+        let listener_cache = cache.clone();
+
+        cache
+            .ensure_listener_started(move || {
+                cache::spawn_invalidation_listener(pool, channel, listener_cache)
+            })
+            .await;
+    }
+
     /// Set the session table schema name with the provided name.
     pub fn with_schema_name(mut self, schema_name: impl AsRef<str>) -> Result<Self, String> {
         let schema_name = schema_name.as_ref();
@@ -87,11 +198,40 @@ impl PostgresStore {
         Ok(self)
     }
 
-    /// Migrate the session schema.
+    /// Migrate the session schema to the latest version.
+    ///
+    /// Applied versions are tracked per `table_name` in a `schema_migrations`
+    /// table in `schema_name`, and pending migrations are applied in order, each in
+    /// its own transaction. A Postgres advisory lock keyed on
+    /// `schema_name`/`table_name` is held for the duration so that
+    /// concurrent app startups don't race to apply the same migration.
     pub async fn migrate(&self) -> Result<(), Error> {
         let mut client = self.pool.get().await?;
-        let tx = client.transaction().await?;
+        let lock_key = self.migration_lock_key();
 
+        client
+            .execute(
+                "select pg_advisory_lock(hashtext($1)::bigint)",
+                &[&lock_key],
+            )
+            .await?;
+
+        let result = self.run_pending_migrations(&mut client).await;
+
+        client
+            .execute(
+                "select pg_advisory_unlock(hashtext($1)::bigint)",
+                &[&lock_key],
+            )
+            .await?;
+
+        result
+    }
+
+    async fn run_pending_migrations(
+        &self,
+        client: &mut deadpool_postgres::Client,
+    ) -> Result<(), Error> {
         let create_schema_query = format!(
             r#"create schema if not exists "{schema_name}""#,
             schema_name = self.schema_name,
@@ -100,49 +240,195 @@ impl PostgresStore {
         // Concurrent create schema may fail due to duplicate key violations.
         //
         // This works around that by assuming the schema must exist on such an error.
-        if let Err(err) = tx.execute(&create_schema_query, &[]).await {
+        if let Err(err) = client.execute(&create_schema_query, &[]).await {
             use tokio_postgres::error::SqlState;
-            if matches!(
+            if !matches!(
                 err.code(),
                 Some(&SqlState::DUPLICATE_SCHEMA | &SqlState::UNIQUE_VIOLATION)
             ) {
-                return Ok(());
+                return Err(err.into());
             }
-
-            return Err(err.into());
         }
 
-        let create_table_query = format!(
+        let create_migrations_table_query = format!(
             r#"
-            create table if not exists "{schema_name}"."{table_name}"
+            create table if not exists "{schema_name}".schema_migrations
             (
-                id text primary key not null,
-                data bytea not null,
-                expiry_date timestamptz not null
+                table_name text not null,
+                version integer not null,
+                applied_at timestamptz not null default (now() at time zone 'utc'),
+                primary key (table_name, version)
             )
             "#,
             schema_name = self.schema_name,
-            table_name = self.table_name
         );
-        tx.execute(&create_table_query, &[]).await?;
+        client.execute(&create_migrations_table_query, &[]).await?;
 
-        tx.commit().await?;
+        let applied_versions: Vec<i32> = client
+            .query(
+                &format!(
+                    r#"select version from "{schema_name}".schema_migrations where table_name = $1"#,
+                    schema_name = self.schema_name,
+                ),
+                &[&self.table_name],
+            )
+            .await?
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        let data_column_type = match self.data_format {
+            DataFormat::Binary(_) => "bytea",
+            DataFormat::Json => "jsonb",
+        };
+
+        // The table migration (version 1) has already run, so the `data`
+        // column exists with whatever type it was created with. Since later
+        // calls to `migrate` never re-run that step, switching
+        // `with_json_data()` on or off against an existing table would
+        // otherwise silently no-op here and surface as an opaque decode
+        // error from `load`/`save` instead.
+        if applied_versions.contains(&1) {
+            if let Some(actual) = self.existing_data_column_type(client).await? {
+                if actual != data_column_type {
+                    return Err(Error::DataColumnMismatch {
+                        table: format!("{}.{}", self.schema_name, self.table_name),
+                        expected: data_column_type,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        for &(version, render_sql) in migrations::steps() {
+            if applied_versions.contains(&version) {
+                continue;
+            }
+
+            let tx = client.transaction().await?;
+            tx.execute(
+                &render_sql(&self.schema_name, &self.table_name, data_column_type),
+                &[],
+            )
+            .await?;
+            tx.execute(
+                &format!(
+                    r#"insert into "{schema_name}".schema_migrations (table_name, version) values ($1, $2)"#,
+                    schema_name = self.schema_name,
+                ),
+                &[&self.table_name, &version],
+            )
+            .await?;
+            tx.commit().await?;
+        }
 
         Ok(())
     }
 
-    async fn id_exists(&self, conn: &impl GenericClient, id: &Id) -> Result<bool, Error> {
+    /// Returns the live `data_type` of the `data` column in
+    /// `information_schema.columns`, or `None` if the table doesn't exist
+    /// yet.
+    async fn existing_data_column_type(
+        &self,
+        client: &deadpool_postgres::Client,
+    ) -> Result<Option<String>, Error> {
+        let row = client
+            .query_opt(
+                r#"
+                select data_type from information_schema.columns
+                where table_schema = $1 and table_name = $2 and column_name = 'data'
+                "#,
+                &[&self.schema_name, &self.table_name],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    fn migration_lock_key(&self) -> String {
+        format!(
+            "tower_sessions_migrate_{}_{}",
+            self.schema_name, self.table_name
+        )
+    }
+
+    /// Extends a session's expiry without rewriting its `data`.
+    ///
+    /// This avoids the full encode + upsert round trip that [`save`](Self::save)
+    /// performs, which is wasted work for sliding-expiry renewals where the
+    /// session's contents haven't changed, only its `expiry_date`. Returns
+    /// `true` if a session with this `id` was found and updated.
+    pub async fn touch(&self, id: &Id, expiry_date: OffsetDateTime) -> Result<bool, Error> {
+        self.ensure_cache_listener().await;
+
         let query = format!(
             r#"
-            select exists(select 1 from "{schema_name}"."{table_name}" where id = $1)
+            update "{schema_name}"."{table_name}"
+            set expiry_date = $2
+            where id = $1
             "#,
             schema_name = self.schema_name,
             table_name = self.table_name
         );
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+
+        let rows_affected = tx.execute(&query, &[&id.to_string(), &expiry_date]).await?;
+        tx.execute(
+            "select pg_notify($1, $2)",
+            &[&self.invalidation_channel(), &id.to_string()],
+        )
+        .await?;
+
+        tx.commit().await?;
 
-        Ok(conn.query_one(&query, &[&id.to_string()]).await?.get(0))
+        if rows_affected > 0 {
+            if let Some(cache) = &self.cache {
+                if let Some(mut record) = cache.get(id).await {
+                    record.expiry_date = expiry_date;
+                    cache.insert(record).await;
+                }
+            }
+        }
+
+        Ok(rows_affected > 0)
     }
 
+    /// Runs `query` (an `insert ... values ($1, $2, $3)` with an `id`,
+    /// `data` and `expiry_date` parameter, in that order) with `record`
+    /// encoded according to `data_format`, returning the number of rows
+    /// affected.
+    async fn execute_upsert(
+        &self,
+        conn: &impl GenericClient,
+        query: &str,
+        record: &Record,
+    ) -> Result<u64, Error> {
+        match &self.data_format {
+            DataFormat::Binary(codec) => Ok(conn
+                .execute(
+                    query,
+                    &[
+                        &record.id.to_string(),
+                        &codec.encode(record)?,
+                        &record.expiry_date,
+                    ],
+                )
+                .await?),
+            DataFormat::Json => Ok(conn
+                .execute(
+                    query,
+                    &[
+                        &record.id.to_string(),
+                        &serde_json::to_value(&record.data).map_err(Error::Json)?,
+                        &record.expiry_date,
+                    ],
+                )
+                .await?),
+        }
+    }
+
+    /// Inserts or overwrites `record` unconditionally.
     async fn save_with_conn(
         &self,
         conn: &impl GenericClient,
@@ -160,23 +446,58 @@ impl PostgresStore {
             schema_name = self.schema_name,
             table_name = self.table_name
         );
+        self.execute_upsert(conn, &query, record).await?;
+
         conn.execute(
-            &query,
-            &[
-                &record.id.to_string(),
-                &rmp_serde::to_vec(&record).map_err(Error::Encode)?,
-                &record.expiry_date,
-            ],
+            "select pg_notify($1, $2)",
+            &[&self.invalidation_channel(), &record.id.to_string()],
         )
         .await?;
 
         Ok(())
     }
+
+    /// Inserts `record`, leaving any existing row with the same `id`
+    /// untouched. Returns `false` (instead of clobbering the existing row)
+    /// if `id` was already taken.
+    async fn create_with_conn(
+        &self,
+        conn: &impl GenericClient,
+        record: &Record,
+    ) -> Result<bool, Error> {
+        let query = format!(
+            r#"
+            insert into "{schema_name}"."{table_name}" (id, data, expiry_date)
+            values ($1, $2, $3)
+            on conflict (id) do nothing
+            "#,
+            schema_name = self.schema_name,
+            table_name = self.table_name
+        );
+        if self.execute_upsert(conn, &query, record).await? == 0 {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "select pg_notify($1, $2)",
+            &[&self.invalidation_channel(), &record.id.to_string()],
+        )
+        .await?;
+
+        Ok(true)
+    }
 }
 
-#[async_trait]
-impl ExpiredDeletion for PostgresStore {
-    async fn delete_expired(&self) -> session_store::Result<()> {
+impl PostgresStore {
+    /// Runs the `delete_expired` query on an already-acquired connection.
+    ///
+    /// Shared by [`ExpiredDeletion::delete_expired`] and
+    /// [`delete_expired_locked`](Self::delete_expired_locked), which must
+    /// reuse the connection it already holds the advisory lock on rather
+    /// than asking the pool for a second one: with a pool sized to a single
+    /// connection, that second `pool.get()` would block forever behind the
+    /// one `delete_expired_locked` itself is holding.
+    async fn delete_expired_with_conn(&self, conn: &impl GenericClient) -> Result<(), Error> {
         let query = format!(
             r#"
             delete from "{schema_name}"."{table_name}"
@@ -185,72 +506,217 @@ impl ExpiredDeletion for PostgresStore {
             schema_name = self.schema_name,
             table_name = self.table_name
         );
+        conn.execute(&query, &[]).await.map_err(Error::Pg)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for PostgresStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
         let client = self.pool.get().await.map_err(Error::Pool)?;
-        client.execute(&query, &[]).await.map_err(Error::Pg)?;
+        self.delete_expired_with_conn(&client).await?;
         Ok(())
     }
 }
 
+impl PostgresStore {
+    /// Spawns a background task that calls [`delete_expired`](ExpiredDeletion::delete_expired)
+    /// on a `period` interval until the task is aborted through the returned
+    /// handle; dropping the handle detaches the task rather than stopping it.
+    ///
+    /// When this is run from multiple app instances sharing one database, an
+    /// advisory lock keyed on `schema_name`/`table_name` ensures only one
+    /// instance performs the sweep per tick, so the others skip theirs
+    /// rather than racing a redundant full-table delete. Errors are logged
+    /// and otherwise swallowed so a transient failure doesn't kill the loop.
+    pub fn continuously_delete_expired(
+        self,
+        period: std::time::Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.delete_expired_locked().await {
+                    tracing::error!(error = %err, "periodic expired session deletion failed");
+                }
+            }
+        })
+    }
+
+    async fn delete_expired_locked(&self) -> Result<(), Error> {
+        let client = self.pool.get().await?;
+        let lock_key = self.advisory_lock_key();
+
+        let acquired: bool = client
+            .query_one(
+                "select pg_try_advisory_lock(hashtext($1)::bigint)",
+                &[&lock_key],
+            )
+            .await?
+            .get(0);
+
+        if !acquired {
+            return Ok(());
+        }
+
+        // Run the delete on the connection we already hold the lock on,
+        // rather than calling the public `delete_expired` (which would ask
+        // the pool for a second connection and, on a single-connection
+        // pool, deadlock behind the one we're holding here).
+        let result = self.delete_expired_with_conn(&client).await;
+
+        client
+            .execute(
+                "select pg_advisory_unlock(hashtext($1)::bigint)",
+                &[&lock_key],
+            )
+            .await?;
+
+        result?;
+        Ok(())
+    }
+
+    fn advisory_lock_key(&self) -> String {
+        format!(
+            "tower_sessions_delete_expired_{}_{}",
+            self.schema_name, self.table_name
+        )
+    }
+}
+
 #[async_trait]
 impl SessionStore for PostgresStore {
     async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        self.ensure_cache_listener().await;
+
         let mut client = self.pool.get().await.map_err(Error::Pool)?;
-        let tx = client.transaction().await.map_err(Error::Pg)?;
 
-        while self.id_exists(&tx, &record.id).await? {
+        for _ in 0..MAX_CREATE_ATTEMPTS {
+            let tx = client.transaction().await.map_err(Error::Pg)?;
+
+            if self.create_with_conn(&tx, record).await? {
+                tx.commit().await.map_err(Error::Pg)?;
+
+                if let Some(cache) = &self.cache {
+                    cache.insert(record.clone()).await;
+                }
+
+                return Ok(());
+            }
+
+            tx.rollback().await.map_err(Error::Pg)?;
             record.id = Id::default();
         }
 
-        self.save_with_conn(&tx, record).await?;
-        tx.commit().await.map_err(Error::Pg)?;
-        Ok(())
+        Err(Error::IdCollision(MAX_CREATE_ATTEMPTS).into())
     }
 
     async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.ensure_cache_listener().await;
+
+        // When we can tell from the cache that only the expiry date changed
+        // (the common case for sliding-expiry renewals), a cheap `touch`
+        // stands in for the full encode + upsert below.
+        //
+        // This fast path requires `with_cache` to be enabled: without a
+        // cached copy of what's already on disk, there's no way to tell
+        // whether `data` changed without doing the same encode this is
+        // meant to skip, so a plain `PostgresStore::new(pool)` always takes
+        // the full encode + upsert path below.
+        if let Some(cache) = &self.cache {
+            if let Some(cached) = cache.get(&record.id).await {
+                if cached.data == record.data && self.touch(&record.id, record.expiry_date).await? {
+                    return Ok(());
+                }
+            }
+        }
+
         let mut client = self.pool.get().await.map_err(Error::Pool)?;
         let tx = client.transaction().await.map_err(Error::Pg)?;
         self.save_with_conn(&tx, record).await?;
         tx.commit().await.map_err(Error::Pg)?;
+
+        if let Some(cache) = &self.cache {
+            cache.insert(record.clone()).await;
+        }
+
         Ok(())
     }
 
     async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        self.ensure_cache_listener().await;
+
+        if let Some(cache) = &self.cache {
+            if let Some(record) = cache.get(session_id).await {
+                return Ok(Some(record));
+            }
+        }
+
         let query = format!(
             r#"
-            select data from "{schema_name}"."{table_name}"
+            select data, expiry_date from "{schema_name}"."{table_name}"
             where id = $1 and expiry_date > $2
             "#,
             schema_name = self.schema_name,
             table_name = self.table_name
         );
         let client = self.pool.get().await.map_err(Error::Pool)?;
-        let record_value: Option<Vec<u8>> = client
+        let row = client
             .query_opt(
                 &query,
                 &[&session_id.to_string(), &OffsetDateTime::now_utc()],
             )
             .await
-            .map_err(Error::Pg)?
-            .map(|row| row.get(0));
+            .map_err(Error::Pg)?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let record: Record = match &self.data_format {
+            DataFormat::Binary(codec) => codec.decode(&row.get::<_, Vec<u8>>(0))?,
+            DataFormat::Json => Record {
+                id: *session_id,
+                data: serde_json::from_value(row.get(0)).map_err(Error::Json)?,
+                expiry_date: row.get(1),
+            },
+        };
 
-        if let Some(data) = record_value {
-            Ok(Some(rmp_serde::from_slice(&data).map_err(Error::Decode)?))
-        } else {
-            Ok(None)
+        if let Some(cache) = &self.cache {
+            cache.insert(record.clone()).await;
         }
+
+        Ok(Some(record))
     }
 
     async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.ensure_cache_listener().await;
+
         let query = format!(
             r#"delete from "{schema_name}"."{table_name}" where id = $1"#,
             schema_name = self.schema_name,
             table_name = self.table_name
         );
-        let client = self.pool.get().await.map_err(Error::Pool)?;
-        client
-            .execute(&query, &[&session_id.to_string()])
+        let mut client = self.pool.get().await.map_err(Error::Pool)?;
+        let tx = client.transaction().await.map_err(Error::Pg)?;
+
+        tx.execute(&query, &[&session_id.to_string()])
             .await
             .map_err(Error::Pg)?;
+        tx.execute(
+            "select pg_notify($1, $2)",
+            &[&self.invalidation_channel(), &session_id.to_string()],
+        )
+        .await
+        .map_err(Error::Pg)?;
+
+        tx.commit().await.map_err(Error::Pg)?;
+
+        if let Some(cache) = &self.cache {
+            cache.invalidate(session_id).await;
+        }
 
         Ok(())
     }
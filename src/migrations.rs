@@ -0,0 +1,33 @@
+/// A single, idempotent schema migration step, keyed by a monotonically
+/// increasing version number. Steps take the store's `schema_name`,
+/// `table_name` and `data` column type and render the DDL to run.
+pub(crate) type MigrationSql =
+    fn(schema_name: &str, table_name: &str, data_column_type: &str) -> String;
+
+/// All migrations in the order they must be applied.
+pub(crate) fn steps() -> &'static [(i32, MigrationSql)] {
+    &[(1, create_table), (2, create_expiry_date_index)]
+}
+
+fn create_table(schema_name: &str, table_name: &str, data_column_type: &str) -> String {
+    format!(
+        r#"
+        create table if not exists "{schema_name}"."{table_name}"
+        (
+            id text primary key not null,
+            data {data_column_type} not null,
+            expiry_date timestamptz not null
+        )
+        "#
+    )
+}
+
+fn create_expiry_date_index(
+    schema_name: &str,
+    table_name: &str,
+    _data_column_type: &str,
+) -> String {
+    format!(
+        r#"create index if not exists "{table_name}_expiry_date_idx" on "{schema_name}"."{table_name}" (expiry_date)"#
+    )
+}
@@ -1,6 +1,8 @@
 mod setup;
 
-use time::Duration;
+use std::collections::HashMap;
+
+use time::{Duration, OffsetDateTime};
 use tower_cookies::Cookie;
 
 use axum::body::Body;
@@ -9,6 +11,12 @@ use tower_cookies::cookie::SameSite;
 
 use tower::util::ServiceExt;
 
+use tower_sessions_core::{
+    session::{Id, Record},
+    SessionStore,
+};
+use tower_sessions_postgres_store::PostgresStore;
+
 use setup::*;
 
 #[tokio::test]
@@ -246,3 +254,219 @@ async fn flush_session() {
     assert_eq!(session_cookie.value(), "");
     assert_eq!(session_cookie.max_age(), Some(Duration::ZERO));
 }
+
+fn new_record(data: HashMap<String, serde_json::Value>) -> Record {
+    Record {
+        id: Id::default(),
+        data,
+        expiry_date: OffsetDateTime::now_utc() + Duration::hours(1),
+    }
+}
+
+#[tokio::test]
+async fn cache_invalidated_across_instances() {
+    let pool = build_pool();
+    let table_name = unique_table_name("cache_invalidation");
+
+    let store_a = PostgresStore::new(pool.clone())
+        .with_table_name(&table_name)
+        .unwrap()
+        .with_cache(10);
+    store_a.migrate().await.unwrap();
+
+    let store_b = PostgresStore::new(pool)
+        .with_table_name(&table_name)
+        .unwrap()
+        .with_cache(10);
+
+    let mut record = new_record(HashMap::from([("foo".to_string(), serde_json::json!(1))]));
+    store_a.create(&mut record).await.unwrap();
+
+    // Populate store_b's cache.
+    let loaded = store_b.load(&record.id).await.unwrap().unwrap();
+    assert_eq!(loaded.data, record.data);
+
+    // store_a overwrites the session; store_b should pick up the change
+    // rather than keep serving its now-stale cached copy.
+    record.data = HashMap::from([("foo".to_string(), serde_json::json!(2))]);
+    store_a.save(&record).await.unwrap();
+
+    // Give store_b's LISTEN task time to receive and process the notification.
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    let loaded = store_b.load(&record.id).await.unwrap().unwrap();
+    assert_eq!(loaded.data, record.data);
+}
+
+#[tokio::test]
+async fn create_retries_on_id_collision() {
+    let pool = build_pool();
+    let table_name = unique_table_name("id_collision");
+
+    let store = PostgresStore::new(pool)
+        .with_table_name(&table_name)
+        .unwrap();
+    store.migrate().await.unwrap();
+
+    let mut existing = new_record(HashMap::new());
+    store.create(&mut existing).await.unwrap();
+
+    // Force a collision: ask `create` to reuse an id that's already taken.
+    let mut colliding = new_record(HashMap::new());
+    colliding.id = existing.id;
+    store.create(&mut colliding).await.unwrap();
+
+    assert_ne!(colliding.id, existing.id);
+    assert!(store.load(&existing.id).await.unwrap().is_some());
+    assert!(store.load(&colliding.id).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn migrate_is_idempotent() {
+    let pool = build_pool();
+    let schema_name = unique_table_name("migrate_schema");
+    let table_name = unique_table_name("migrate_table");
+
+    let store = PostgresStore::new(pool.clone())
+        .with_schema_name(&schema_name)
+        .unwrap()
+        .with_table_name(&table_name)
+        .unwrap();
+
+    store.migrate().await.unwrap();
+    store.migrate().await.unwrap();
+
+    let client = pool.get().await.unwrap();
+    let row = client
+        .query_one(
+            &format!(r#"select count(*) from "{schema_name}".schema_migrations"#),
+            &[],
+        )
+        .await
+        .unwrap();
+    let applied_migration_count: i64 = row.get(0);
+
+    assert_eq!(applied_migration_count, 2);
+}
+
+#[tokio::test]
+async fn json_data_round_trips_and_is_queryable_at_documented_path() {
+    let pool = build_pool();
+    let table_name = unique_table_name("json_data");
+
+    let store = PostgresStore::new(pool.clone())
+        .with_table_name(&table_name)
+        .unwrap()
+        .with_json_data();
+    store.migrate().await.unwrap();
+
+    let mut record = new_record(HashMap::from([(
+        "user_id".to_string(),
+        serde_json::json!(42),
+    )]));
+    store.create(&mut record).await.unwrap();
+
+    let loaded = store.load(&record.id).await.unwrap().unwrap();
+    assert_eq!(loaded.data, record.data);
+    assert_eq!(
+        loaded.expiry_date.unix_timestamp(),
+        record.expiry_date.unix_timestamp()
+    );
+
+    // The with_json_data doc promises `data->>'user_id'` reaches the
+    // session payload directly, not `data->'data'->>'user_id'`.
+    let client = pool.get().await.unwrap();
+    let row = client
+        .query_one(
+            &format!(
+                r#"select data->>'user_id' from "tower_sessions"."{table_name}" where id = $1"#
+            ),
+            &[&record.id.to_string()],
+        )
+        .await
+        .unwrap();
+    let user_id: Option<String> = row.get(0);
+    assert_eq!(user_id.as_deref(), Some("42"));
+}
+
+#[tokio::test]
+async fn save_takes_touch_fast_path_for_unchanged_data() {
+    let pool = build_pool();
+    let table_name = unique_table_name("save_touch_fast_path");
+
+    let store = PostgresStore::new(pool)
+        .with_table_name(&table_name)
+        .unwrap()
+        .with_cache(10);
+    store.migrate().await.unwrap();
+
+    let mut record = new_record(HashMap::from([("foo".to_string(), serde_json::json!(1))]));
+    store.create(&mut record).await.unwrap();
+
+    // Same data, later expiry: save() should take the touch fast path
+    // rather than a full encode + upsert, but the end result -- the new
+    // expiry persisted, the data untouched -- must be identical either way.
+    let new_expiry = OffsetDateTime::now_utc() + Duration::hours(2);
+    record.expiry_date = new_expiry;
+    store.save(&record).await.unwrap();
+
+    let loaded = store.load(&record.id).await.unwrap().unwrap();
+    assert_eq!(loaded.data, record.data);
+    assert_eq!(
+        loaded.expiry_date.unix_timestamp(),
+        new_expiry.unix_timestamp()
+    );
+}
+
+#[tokio::test]
+async fn continuously_delete_expired_works_on_a_single_connection_pool() {
+    let database_url = std::option_env!("DATABASE_URL").expect("DATABASE_URL must be set");
+    let manager =
+        deadpool_postgres::Manager::new(database_url.parse().unwrap(), tokio_postgres::NoTls);
+    let pool = deadpool_postgres::Pool::builder(manager)
+        .max_size(1)
+        .build()
+        .unwrap();
+    let table_name = unique_table_name("delete_expired_pool1");
+
+    let store = PostgresStore::new(pool.clone())
+        .with_table_name(&table_name)
+        .unwrap();
+    store.migrate().await.unwrap();
+
+    let mut expired = new_record(HashMap::new());
+    expired.expiry_date = OffsetDateTime::now_utc() - Duration::seconds(1);
+    store.create(&mut expired).await.unwrap();
+
+    // With only one connection in the pool, delete_expired_locked must run
+    // its delete on the connection it already holds the advisory lock on;
+    // asking the pool for a second one here would deadlock forever.
+    let handle = store
+        .clone()
+        .continuously_delete_expired(tokio::time::Duration::from_millis(50));
+
+    let swept = tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+        loop {
+            let client = pool.get().await.unwrap();
+            let row = client
+                .query_one(
+                    &format!(r#"select count(*) from "tower_sessions"."{table_name}""#),
+                    &[],
+                )
+                .await
+                .unwrap();
+            let remaining: i64 = row.get(0);
+            if remaining == 0 {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await;
+
+    handle.abort();
+    assert!(
+        swept.is_ok(),
+        "continuously_delete_expired deadlocked on a single-connection pool"
+    );
+}
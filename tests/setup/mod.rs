@@ -1,3 +1,5 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use axum::{routing::get, Router};
 use http::{header, HeaderMap};
 use http_body_util::BodyExt;
@@ -8,6 +10,16 @@ use tower_sessions::{Expiry, Session, SessionManagerLayer, SessionStore};
 use axum::body::Body;
 use tower_sessions_postgres_store::PostgresStore;
 
+static TABLE_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A table name unique to this test process and call, so tests that poke at
+/// store internals (migrations, caching) don't trip over each other or over
+/// the shared "session" table the HTTP-level tests migrate.
+pub fn unique_table_name(prefix: &str) -> String {
+    let n = TABLE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{prefix}_{}_{n}", std::process::id())
+}
+
 fn routes() -> Router {
     Router::new()
         .route("/", get(|_: Session| async move { "Hello, world!" }))
@@ -71,11 +83,15 @@ pub async fn body_string(body: Body) -> String {
     String::from_utf8_lossy(&bytes).into()
 }
 
-pub async fn create_app(max_age: Option<Duration>) -> Router {
+pub fn build_pool() -> deadpool_postgres::Pool {
     let database_url = std::option_env!("DATABASE_URL").expect("DATABASE_URL must be set");
     let manager =
         deadpool_postgres::Manager::new(database_url.parse().unwrap(), tokio_postgres::NoTls);
-    let pool = deadpool_postgres::Pool::builder(manager).build().unwrap();
+    deadpool_postgres::Pool::builder(manager).build().unwrap()
+}
+
+pub async fn create_app(max_age: Option<Duration>) -> Router {
+    let pool = build_pool();
     let session_store = PostgresStore::new(pool);
     session_store.migrate().await.unwrap();
     let session_manager = SessionManagerLayer::new(session_store).with_secure(true);